@@ -3,16 +3,34 @@ use cpal::{
     BufferSize, BuildStreamError, Data, Device, InputCallbackInfo, PauseStreamError,
     PlayStreamError, SampleFormat, SampleRate, StreamConfig, StreamError,
 };
+use ringbuf::HeapRb;
 use std::ops::DerefMut;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
 use thiserror::Error;
 use serde::Deserialize;
 
 use midir::MidiOutputConnection;
 
+pub mod loop_detect;
+pub mod sfz;
+pub mod smf;
+pub mod wav;
+
 pub type NoteSample = Vec<f32>;
 
+/// A single captured note: the MIDI note and velocity it was played at,
+/// alongside the recorded audio and, if one was found, a seamless sustain
+/// loop (see [`loop_detect::find_loop_points`]).
+#[derive(Debug, Clone)]
+pub struct CapturedNote {
+    pub note: u8,
+    pub velocity: u8,
+    pub samples: NoteSample,
+    pub loop_points: Option<loop_detect::LoopPoints>,
+}
+
 #[derive(Error, Debug)]
 pub enum CaptureError {
     #[error("could not build input stream")]
@@ -29,23 +47,49 @@ pub enum CaptureError {
 
     #[error("could not send midi message")]
     MidiSend(#[from] midir::SendError),
+
+    #[error("capture consumer thread disconnected")]
+    ConsumerDisconnected(#[from] mpsc::RecvError),
 }
 
 
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+/// How a note's release is timed.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub enum ReleaseDetection {
+    /// Hold for a fixed `time_release` duration after Note Off.
+    Fixed,
+    /// Keep recording after Note Off until the signal's RMS stays below
+    /// `silence_threshold_db` for `decay_hold`, or `max_time_release`
+    /// elapses, whichever comes first.
+    AdaptiveDecay {
+        silence_threshold_db: f32,
+        decay_hold: Duration,
+        max_time_release: Duration,
+    },
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct NoteCaptureSettings {
     time_on: Duration,
     time_release: Duration,
+    release_detection: ReleaseDetection,
     time_between: Duration,
-    channels: u8,
-    sample_rate: usize,
+    pub(crate) channels: u8,
+    pub(crate) sample_rate: usize,
     midi_channel: u8,
-    note_on_velocity: u8,
+    pub(crate) velocities: Vec<u8>,
     note_off_velocity: u8,
 
-    first_note: u8,
-    last_note: u8,
+    pub(crate) first_note: u8,
+    pub(crate) last_note: u8,
     note_spacing: u8,
+
+    pub(crate) wav_format: wav::WavSampleFormat,
+
+    /// Capacity, in samples, of the ring buffer handing audio off from the
+    /// input stream's callback to the capture consumer thread used by
+    /// [`NoteCapturer::capture_notes_streaming`].
+    pub(crate) ring_buffer_capacity: usize,
 }
 
 pub struct NoteCapturer<'d> {
@@ -58,16 +102,20 @@ impl Default for NoteCaptureSettings {
 	Self {
             time_on: Duration::from_secs_f32(0.02),
             time_release: Duration::from_secs_f32(0.02),
+            release_detection: ReleaseDetection::Fixed,
 	    time_between: Duration::from_secs_f32(1.0),
             channels: 1,
             sample_rate: 44100,
             midi_channel: 1,
-            note_on_velocity: 64,
+            velocities: vec![64],
             note_off_velocity: 64,
 
             first_note: 21,
             last_note: 108,
             note_spacing: 1,
+
+            wav_format: wav::WavSampleFormat::Pcm16,
+            ring_buffer_capacity: 16384,
 	}
     }
 }
@@ -77,8 +125,13 @@ impl NoteCaptureSettings {
     /// needed to store each note.
     fn num_samples(&self) -> usize {
         let num_channels: u16 = self.channels as u16;
-        let total_length_secs: f32 =
-            self.time_on.as_secs_f32() + self.time_release.as_secs_f32() + 0.01;
+        let release_secs = match &self.release_detection {
+            ReleaseDetection::Fixed => self.time_release.as_secs_f32(),
+            ReleaseDetection::AdaptiveDecay {
+                max_time_release, ..
+            } => max_time_release.as_secs_f32(),
+        };
+        let total_length_secs: f32 = self.time_on.as_secs_f32() + release_secs + 0.01;
         ((self.sample_rate * num_channels as usize) as f32 * total_length_secs) as usize
     }
 
@@ -88,11 +141,145 @@ impl NoteCaptureSettings {
 	    return false;
 	}
 
+	if self.velocities.is_empty() {
+	    return false;
+	}
+
+	if self.ring_buffer_capacity == 0 {
+	    return false;
+	}
+
+	if self.note_spacing == 0 {
+	    return false;
+	}
+
 	true
     }
 }
     
 
+/// Shared adaptive (RMS-decay) release-detection state, used by both
+/// [`NoteCapturer::capture_note_list`] and
+/// [`NoteCapturer::capture_note_list_streaming`] so the RMS/dB computation
+/// and release-wait logic is only implemented once.
+///
+/// While monitoring, [`ReleaseMonitorHandle::observe_block`] (called from the
+/// audio callback) accumulates a running count of consecutive
+/// below-`silence_threshold_db` samples and flips `release_done` once that
+/// run covers `decay_hold`; [`ReleaseMonitor::wait_for_release`] (called from
+/// the capture loop) starts/stops monitoring and polls for that flag.
+struct ReleaseMonitor {
+    monitoring: Arc<AtomicBool>,
+    release_done: Arc<AtomicBool>,
+    silent_samples: Arc<AtomicUsize>,
+    silence_threshold_db: f32,
+    decay_hold_secs: f32,
+    num_channels: usize,
+    sample_rate: usize,
+}
+
+/// The half of a [`ReleaseMonitor`] that the audio callback needs; cloning
+/// this only clones the shared atomics, not the monitor itself.
+#[derive(Clone)]
+struct ReleaseMonitorHandle {
+    monitoring: Arc<AtomicBool>,
+    release_done: Arc<AtomicBool>,
+    silent_samples: Arc<AtomicUsize>,
+    silence_threshold_db: f32,
+    decay_hold_secs: f32,
+    num_channels: usize,
+    sample_rate: usize,
+}
+
+impl ReleaseMonitor {
+    fn new(settings: &NoteCaptureSettings) -> Self {
+        let (silence_threshold_db, decay_hold_secs) = match &settings.release_detection {
+            ReleaseDetection::Fixed => (0.0, 0.0),
+            ReleaseDetection::AdaptiveDecay {
+                silence_threshold_db,
+                decay_hold,
+                ..
+            } => (*silence_threshold_db, decay_hold.as_secs_f32()),
+        };
+
+        Self {
+            monitoring: Arc::new(AtomicBool::new(false)),
+            release_done: Arc::new(AtomicBool::new(false)),
+            silent_samples: Arc::new(AtomicUsize::new(0)),
+            silence_threshold_db,
+            decay_hold_secs,
+            num_channels: settings.channels as usize,
+            sample_rate: settings.sample_rate,
+        }
+    }
+
+    /// A cloneable handle carrying the shared atomics, for moving into the
+    /// audio callback closure.
+    fn handle(&self) -> ReleaseMonitorHandle {
+        ReleaseMonitorHandle {
+            monitoring: self.monitoring.clone(),
+            release_done: self.release_done.clone(),
+            silent_samples: self.silent_samples.clone(),
+            silence_threshold_db: self.silence_threshold_db,
+            decay_hold_secs: self.decay_hold_secs,
+            num_channels: self.num_channels,
+            sample_rate: self.sample_rate,
+        }
+    }
+
+    /// Block until the note's release has finished: sleep `time_release` if
+    /// `release_detection` is `Fixed`, or monitor RMS decay (capped at
+    /// `max_time_release`) if it's `AdaptiveDecay`.
+    fn wait_for_release(&self, release_detection: &ReleaseDetection, time_release: Duration) {
+        match release_detection {
+            ReleaseDetection::Fixed => {
+                std::thread::sleep(time_release);
+            }
+            ReleaseDetection::AdaptiveDecay {
+                max_time_release, ..
+            } => {
+                self.silent_samples.store(0, Ordering::Relaxed);
+                self.release_done.store(false, Ordering::Relaxed);
+                self.monitoring.store(true, Ordering::Relaxed);
+
+                let start = Instant::now();
+                while !self.release_done.load(Ordering::Relaxed)
+                    && start.elapsed() < *max_time_release
+                {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+
+                self.monitoring.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl ReleaseMonitorHandle {
+    /// Feed one block of interleaved audio from the data callback. A no-op
+    /// unless a release wait (see [`ReleaseMonitor::wait_for_release`]) is
+    /// currently in progress.
+    fn observe_block(&self, block: &[f32]) {
+        if !self.monitoring.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let sum_sq: f32 = block.iter().map(|s| s * s).sum();
+        let rms = (sum_sq / block.len().max(1) as f32).sqrt();
+        let db = 20.0 * rms.max(1e-9).log10();
+
+        if db < self.silence_threshold_db {
+            let silent = self.silent_samples.fetch_add(block.len(), Ordering::Relaxed) + block.len();
+            let silent_secs = silent as f32 / (self.sample_rate * self.num_channels) as f32;
+            if silent_secs >= self.decay_hold_secs {
+                self.release_done.store(true, Ordering::Relaxed);
+            }
+        } else {
+            self.silent_samples.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
 impl<'d> NoteCapturer<'d> {
     /// Return a new note capturer with standard settings.
     pub fn new(input_device: &Device) -> NoteCapturer {
@@ -118,11 +305,10 @@ impl<'d> NoteCapturer<'d> {
         [0x90 | (channel & 0xF), note, velocity]
     }
 
-    /// Capture the collection of notes in the range from first_note to last_note.
-    pub fn capture_notes(
-        &self,
-        midi: &mut MidiOutputConnection,
-    ) -> Result<Vec<NoteSample>, CaptureError> {
+    /// Return the notes, in order, that `capture_notes` will sample given the
+    /// configured range and spacing. The last note in the range is always
+    /// included, even if it falls off the regular spacing.
+    pub fn sampled_notes(&self) -> Vec<u8> {
         let mut notes: Vec<u8> = (self.settings.first_note..=self.settings.last_note)
             .enumerate()
             .filter_map(|(i, n)| {
@@ -140,15 +326,32 @@ impl<'d> NoteCapturer<'d> {
 	    }
 	}
 
-        self.capture_note_list(midi, &notes)
+        notes
     }
 
-    /// Capture a list of notes in order.
+    /// Capture the collection of notes in the range from first_note to
+    /// last_note, once per configured velocity layer. If `recorder` is
+    /// given, every Note On/Off sent is logged to it for later export as a
+    /// Standard MIDI File.
+    pub fn capture_notes(
+        &self,
+        midi: &mut MidiOutputConnection,
+        recorder: Option<&mut smf::SessionRecorder>,
+    ) -> Result<Vec<CapturedNote>, CaptureError> {
+        let notes = self.sampled_notes();
+
+        self.capture_note_list(midi, &notes, recorder)
+    }
+
+    /// Capture a list of notes in order, once per configured velocity layer.
+    /// Note is the outer loop and velocity is the inner loop, so that, for a
+    /// given note, layers are captured back to back.
     fn capture_note_list(
         &self,
         midi: &mut MidiOutputConnection,
         notes: &[u8],
-    ) -> Result<Vec<NoteSample>, CaptureError> {
+        mut recorder: Option<&mut smf::SessionRecorder>,
+    ) -> Result<Vec<CapturedNote>, CaptureError> {
         let max_size = self.settings.num_samples();
         let buffer: Arc<Mutex<Vec<f32>>> = Arc::new(Mutex::new(Vec::new()));
 
@@ -168,10 +371,17 @@ impl<'d> NoteCapturer<'d> {
 
         let b2 = buffer.clone();
 
+        let release_monitor = ReleaseMonitor::new(&self.settings);
+        let monitor_handle = release_monitor.handle();
+
         let mut total_num_samples = 0;
         let data_callback = move |d: &Data, _ici: &InputCallbackInfo| {
+            let block = d.as_slice().unwrap();
+
+            monitor_handle.observe_block(block);
+
             let mut buf = b2.lock().unwrap();
-            for s in d.as_slice().unwrap() {
+            for s in block {
                 if total_num_samples >= max_size {
                     break;
                 }
@@ -188,44 +398,278 @@ impl<'d> NoteCapturer<'d> {
         )?;
 
         for note in notes {
-            {
-                let mut b = buffer.lock().unwrap();
-                b.clear();
-                b.reserve(max_size);
+            for velocity in &self.settings.velocities {
+                {
+                    let mut b = buffer.lock().unwrap();
+                    b.clear();
+                    b.reserve(max_size);
+                }
+
+                {
+                    let note_on = Self::midi_note_on_message(
+                        self.settings.midi_channel,
+                        *note,
+                        *velocity,
+                    );
+                    midi.send(&note_on)?;
+                    if let Some(r) = recorder.as_mut() {
+                        r.record(note_on);
+                    }
+
+                    stream.play()?;
+                    std::thread::sleep(self.settings.time_on);
+
+                    let note_off = Self::midi_note_off_message(
+                        self.settings.midi_channel,
+                        *note,
+                        self.settings.note_off_velocity,
+                    );
+                    midi.send(&note_off)?;
+                    if let Some(r) = recorder.as_mut() {
+                        r.record(note_off);
+                    }
+
+                    release_monitor
+                        .wait_for_release(&self.settings.release_detection, self.settings.time_release);
+
+                    stream.pause()?;
+                }
+                {
+                    if error.lock().unwrap().is_some() {
+                        let lock = Arc::try_unwrap(error).expect("should be no lock");
+                        let stream_error = lock.into_inner().expect("should not be poisoned").unwrap();
+                        return Err(CaptureError::Stream(stream_error));
+                    }
+                }
+
+                let mut ret_buf: Vec<f32> = Vec::new();
+                let mut raw_buf = buffer.lock().unwrap();
+                std::mem::swap(raw_buf.deref_mut(), &mut ret_buf);
+
+                let loop_points = loop_detect::find_loop_points(
+                    &ret_buf,
+                    self.settings.channels,
+                    self.settings.sample_rate,
+                );
+
+                note_buffers.push(CapturedNote {
+                    note: *note,
+                    velocity: *velocity,
+                    samples: ret_buf,
+                    loop_points,
+                });
+	        std::thread::sleep(self.settings.time_between);
             }
+        }
+
+        Ok(note_buffers)
+    }
+
+    /// Capture the collection of notes in the range from first_note to
+    /// last_note, once per configured velocity layer, calling `on_note` with
+    /// each note as soon as it finishes rather than collecting every note
+    /// into one `Vec` held for the whole session.
+    ///
+    /// The input stream's callback hands samples off to a consumer thread
+    /// through a lock-free ring buffer (sized by `ring_buffer_capacity`),
+    /// so the audio callback itself never blocks on a lock, and only one
+    /// note's worth of audio is ever resident at a time rather than the
+    /// whole session's.
+    pub fn capture_notes_streaming(
+        &self,
+        midi: &mut MidiOutputConnection,
+        recorder: Option<&mut smf::SessionRecorder>,
+        on_note: impl FnMut(CapturedNote),
+    ) -> Result<(), CaptureError> {
+        let notes = self.sampled_notes();
+
+        self.capture_note_list_streaming(midi, &notes, recorder, on_note)
+    }
+
+    /// Streaming counterpart to `capture_note_list`; see
+    /// `capture_notes_streaming`.
+    fn capture_note_list_streaming(
+        &self,
+        midi: &mut MidiOutputConnection,
+        notes: &[u8],
+        mut recorder: Option<&mut smf::SessionRecorder>,
+        mut on_note: impl FnMut(CapturedNote),
+    ) -> Result<(), CaptureError> {
+        let max_size = self.settings.num_samples();
+
+        let in_config = StreamConfig {
+            channels: self.settings.channels.into(),
+            sample_rate: SampleRate(self.settings.sample_rate as u32),
+            buffer_size: BufferSize::Default,
+        };
+
+        let error = Arc::new(Mutex::new(None));
+        let e2 = error.clone();
+        let error_callback = move |e: StreamError| {
+            *e2.lock().unwrap() = Some(e);
+        };
+
+        // Shared state for adaptive (RMS-decay) release detection; see
+        // `ReleaseMonitor`.
+        let release_monitor = ReleaseMonitor::new(&self.settings);
+        let monitor_handle = release_monitor.handle();
+
+        let num_channels = self.settings.channels as usize;
+        let sample_rate = self.settings.sample_rate;
+
+        let ring = HeapRb::<f32>::new(self.settings.ring_buffer_capacity);
+        let (mut producer, mut consumer) = ring.split();
+
+        let data_callback = move |d: &Data, _ici: &InputCallbackInfo| {
+            let block = d.as_slice().unwrap();
+
+            monitor_handle.observe_block(block);
+
+            for s in block {
+                // Wait-free push; if the consumer thread has fallen behind
+                // and the ring buffer is full, drop the sample rather than
+                // block the audio thread.
+                let _ = producer.push(*s);
+            }
+        };
+
+        let stream = self.device.build_input_stream_raw(
+            &in_config,
+            SampleFormat::F32,
+            data_callback,
+            error_callback,
+        )?;
+
+        // The consumer thread drains the ring buffer into whichever note is
+        // currently being captured, handing each finished note back over
+        // `result_rx` as soon as it is released.
+        enum ConsumerMsg {
+            StartNote { note: u8, velocity: u8 },
+            EndNote,
+            Stop,
+        }
+        let (msg_tx, msg_rx) = mpsc::channel::<ConsumerMsg>();
+        let (result_tx, result_rx) = mpsc::channel::<CapturedNote>();
+
+        let consumer_thread = std::thread::spawn(move || {
+            let mut current: Option<(u8, u8, Vec<f32>)> = None;
+
+            // Drain continuously rather than only between control messages:
+            // the ring buffer is just a hand-off from the audio callback, so
+            // it must never be left to hold more than a few callback-blocks'
+            // worth of audio while we wait for the next `StartNote`/`EndNote`.
+            loop {
+                while let Some(sample) = consumer.pop() {
+                    if let Some((_, _, buf)) = current.as_mut() {
+                        if buf.len() < max_size {
+                            buf.push(sample);
+                        }
+                    }
+                }
+
+                match msg_rx.try_recv() {
+                    Ok(ConsumerMsg::StartNote { note, velocity }) => {
+                        current = Some((note, velocity, Vec::new()));
+                    }
+                    Ok(ConsumerMsg::EndNote) => {
+                        if let Some((note, velocity, samples)) = current.take() {
+                            let loop_points =
+                                loop_detect::find_loop_points(&samples, num_channels as u8, sample_rate);
+                            if result_tx
+                                .send(CapturedNote {
+                                    note,
+                                    velocity,
+                                    samples,
+                                    loop_points,
+                                })
+                                .is_err()
+                            {
+                                return;
+                            }
+                        }
+                    }
+                    Ok(ConsumerMsg::Stop) => return,
+                    Err(mpsc::TryRecvError::Disconnected) => return,
+                    Err(mpsc::TryRecvError::Empty) => {
+                        std::thread::sleep(Duration::from_millis(1));
+                    }
+                }
+            }
+        });
+
+        for note in notes {
+            for velocity in &self.settings.velocities {
+                msg_tx
+                    .send(ConsumerMsg::StartNote {
+                        note: *note,
+                        velocity: *velocity,
+                    })
+                    .expect("consumer thread should still be running");
+
+                let note_on =
+                    Self::midi_note_on_message(self.settings.midi_channel, *note, *velocity);
+                midi.send(&note_on)?;
+                if let Some(r) = recorder.as_mut() {
+                    r.record(note_on);
+                }
 
-            {
-                midi.send(&Self::midi_note_on_message(
-                    self.settings.midi_channel,
-                    *note,
-                    self.settings.note_on_velocity,
-                ))?;
                 stream.play()?;
                 std::thread::sleep(self.settings.time_on);
-                midi.send(&Self::midi_note_off_message(
+
+                let note_off = Self::midi_note_off_message(
                     self.settings.midi_channel,
                     *note,
                     self.settings.note_off_velocity,
-                ))?;
-                std::thread::sleep(self.settings.time_release);
+                );
+                midi.send(&note_off)?;
+                if let Some(r) = recorder.as_mut() {
+                    r.record(note_off);
+                }
+
+                release_monitor
+                    .wait_for_release(&self.settings.release_detection, self.settings.time_release);
+
                 stream.pause()?;
-            }
-            {
+
                 if error.lock().unwrap().is_some() {
                     let lock = Arc::try_unwrap(error).expect("should be no lock");
                     let stream_error = lock.into_inner().expect("should not be poisoned").unwrap();
                     return Err(CaptureError::Stream(stream_error));
                 }
-            }
 
-            let mut ret_buf: Vec<f32> = Vec::new();
-            let mut raw_buf = buffer.lock().unwrap();
-            std::mem::swap(raw_buf.deref_mut(), &mut ret_buf);
+                msg_tx
+                    .send(ConsumerMsg::EndNote)
+                    .expect("consumer thread should still be running");
+                let captured = result_rx.recv()?;
+                on_note(captured);
 
-            note_buffers.push(ret_buf);
-	    std::thread::sleep(self.settings.time_between);
+                std::thread::sleep(self.settings.time_between);
+            }
         }
 
-        Ok(note_buffers)
+        let _ = msg_tx.send(ConsumerMsg::Stop);
+        let _ = consumer_thread.join();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_rejects_zero_note_spacing() {
+        let settings = NoteCaptureSettings {
+            note_spacing: 0,
+            ..NoteCaptureSettings::default()
+        };
+
+        assert!(!settings.verify());
+    }
+
+    #[test]
+    fn verify_accepts_default_settings() {
+        assert!(NoteCaptureSettings::default().verify());
     }
 }
@@ -0,0 +1,162 @@
+//! Automatic loop-point detection for sustained samples.
+
+use crate::NoteSample;
+
+/// Frames per envelope window used to locate the attack/release boundaries.
+const ENVELOPE_WINDOW: usize = 256;
+
+/// Upper bound on how many zero crossings are considered as loop-start/end
+/// candidates. The search is O(n^2) in this count, and a multi-second
+/// sustained note can have thousands of crossings, so once there are more
+/// than this many we stride through them evenly rather than consider all of
+/// them.
+const MAX_CANDIDATE_CROSSINGS: usize = 200;
+
+/// A seamless sustain loop found inside a captured note, as sample offsets
+/// (interleaved frame index, i.e. already multiplied by channel count).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopPoints {
+    pub loop_start: usize,
+    pub loop_end: usize,
+}
+
+/// Search the steady-state region of `sample` for a loop-start/loop-end pair
+/// that can be looped seamlessly: both endpoints sit on a positive-going
+/// zero crossing, and the window following each is the best match under sum
+/// of squared differences, normalized by the two windows' energy, among the
+/// candidates considered.
+///
+/// Returns `None` if `sample` is too short, silent, or has no usable
+/// steady-state region to search.
+pub fn find_loop_points(sample: &NoteSample, channels: u8, sample_rate: usize) -> Option<LoopPoints> {
+    let channels = channels as usize;
+    if channels == 0 || sample_rate == 0 {
+        return None;
+    }
+
+    let num_frames = sample.len() / channels;
+    if num_frames < ENVELOPE_WINDOW * 4 {
+        return None;
+    }
+
+    // Downmix to mono for envelope and zero-crossing analysis; loop points
+    // are reported back in interleaved (all-channel) offsets.
+    let mono: Vec<f32> = (0..num_frames)
+        .map(|f| {
+            let frame = &sample[f * channels..(f + 1) * channels];
+            frame.iter().sum::<f32>() / channels as f32
+        })
+        .collect();
+
+    let envelope: Vec<f32> = mono
+        .chunks(ENVELOPE_WINDOW)
+        .map(|w| (w.iter().map(|s| s * s).sum::<f32>() / w.len() as f32).sqrt())
+        .collect();
+
+    let peak = envelope.iter().cloned().fold(0.0_f32, f32::max);
+    if peak <= 0.0 {
+        return None;
+    }
+
+    // Steady state: from the window the attack first reaches 80% of peak
+    // amplitude, to the last window still at or above 40% of peak, i.e.
+    // before the release tail has meaningfully decayed.
+    let attack_end = envelope.iter().position(|&v| v >= peak * 0.8)?;
+    let release_start = envelope.iter().rposition(|&v| v >= peak * 0.4)?;
+    if release_start <= attack_end {
+        return None;
+    }
+
+    let region_start = attack_end * ENVELOPE_WINDOW;
+    let region_end = (release_start * ENVELOPE_WINDOW).min(num_frames - 1);
+    if region_end <= region_start {
+        return None;
+    }
+
+    // Skip loops shorter than one period of a ~1kHz fundamental (and never
+    // shorter than 32 frames), so very short, click-prone loops aren't
+    // picked. Loop length has no upper bound, and the note's own fundamental
+    // is not estimated.
+    let min_loop_len = (sample_rate / 1000).max(32);
+
+    let zero_crossings: Vec<usize> = (region_start + 1..region_end)
+        .filter(|&i| mono[i - 1] <= 0.0 && mono[i] > 0.0)
+        .collect();
+    if zero_crossings.is_empty() {
+        return None;
+    }
+
+    let stride = ((zero_crossings.len() + MAX_CANDIDATE_CROSSINGS - 1) / MAX_CANDIDATE_CROSSINGS).max(1);
+    let candidates: Vec<usize> = zero_crossings.iter().step_by(stride).copied().collect();
+
+    let mut best: Option<(usize, usize, f32)> = None;
+    for &start in &candidates {
+        for &end in &candidates {
+            if end < start + min_loop_len {
+                continue;
+            }
+
+            let window = min_loop_len.min(num_frames - end);
+            if window == 0 {
+                continue;
+            }
+
+            let mut diff = 0.0;
+            let mut energy = 0.0;
+            for k in 0..window {
+                let a = mono[start + k];
+                let b = mono[end + k];
+                diff += (a - b) * (a - b);
+                energy += a * a + b * b;
+            }
+            let score = diff / energy.max(1e-9);
+
+            if best.map_or(true, |(_, _, best_score)| score < best_score) {
+                best = Some((start, end, score));
+            }
+        }
+    }
+
+    best.map(|(start, end, _)| LoopPoints {
+        loop_start: start * channels,
+        loop_end: end * channels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn periodic_sine(num_frames: usize, period: usize) -> NoteSample {
+        (0..num_frames)
+            .map(|i| (2.0 * std::f32::consts::PI * (i % period) as f32 / period as f32).sin())
+            .collect()
+    }
+
+    #[test]
+    fn too_short_sample_returns_none() {
+        let sample = periodic_sine(100, 10);
+        assert_eq!(find_loop_points(&sample, 1, 44100), None);
+    }
+
+    #[test]
+    fn zero_channels_or_sample_rate_returns_none() {
+        let sample = periodic_sine(4096, 100);
+        assert_eq!(find_loop_points(&sample, 0, 44100), None);
+        assert_eq!(find_loop_points(&sample, 1, 0), None);
+    }
+
+    #[test]
+    fn silent_sample_returns_none() {
+        let sample = vec![0.0; 4096];
+        assert_eq!(find_loop_points(&sample, 1, 44100), None);
+    }
+
+    #[test]
+    fn finds_a_loop_within_a_periodic_signal() {
+        let sample = periodic_sine(8192, 100);
+        let points = find_loop_points(&sample, 1, 44100).expect("should find a loop");
+        assert!(points.loop_start < points.loop_end);
+        assert!(points.loop_end < sample.len());
+    }
+}
@@ -0,0 +1,215 @@
+//! Generating a playable SFZ instrument from a capture session.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::wav::note_file_name;
+use crate::{CapturedNote, NoteCaptureSettings};
+
+/// Split `lo..=hi` across `values` (sorted, ascending) so that the gap
+/// between neighboring values is divided evenly between them.
+fn partition_ranges(values: &[u8], lo: u8, hi: u8) -> Vec<(u8, u8)> {
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, &v)| {
+            let range_lo = if i == 0 { lo } else { (values[i - 1] + v) / 2 + 1 };
+            let range_hi = if i == values.len() - 1 {
+                hi
+            } else {
+                (v + values[i + 1]) / 2
+            };
+            (range_lo, range_hi)
+        })
+        .collect()
+}
+
+/// Return the `(lokey, hikey)` range each sampled note should cover, so that
+/// the gap left by `note_spacing` is split evenly between neighboring
+/// samples. `notes` must be sorted in ascending order.
+pub fn key_ranges(notes: &[u8], first_note: u8, last_note: u8) -> Vec<(u8, u8)> {
+    partition_ranges(notes, first_note, last_note)
+}
+
+/// Return the `(lovel, hivel)` range each velocity layer should cover.
+/// `velocities` is sorted and deduplicated before partitioning, so repeated
+/// values (nothing rejects duplicate velocities in
+/// `NoteCaptureSettings::verify`) can never invert a range.
+pub fn velocity_ranges(velocities: &[u8]) -> Vec<(u8, u8)> {
+    let mut unique: Vec<u8> = velocities.to_vec();
+    unique.sort_unstable();
+    unique.dedup();
+    partition_ranges(&unique, 1, 127)
+}
+
+/// Write an `.sfz` instrument to `path`, with one `<region>` per captured
+/// note/velocity pair, each pointing at the corresponding sample in
+/// `sample_dir` and spanning the key and velocity ranges computed by
+/// [`key_ranges`] and [`velocity_ranges`].
+pub fn export_sfz(
+    notes: &[CapturedNote],
+    settings: &NoteCaptureSettings,
+    sample_dir: impl AsRef<Path>,
+    path: impl AsRef<Path>,
+) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    write_sfz(&mut file, notes, settings, sample_dir.as_ref())
+}
+
+fn write_sfz<W: Write>(
+    writer: &mut W,
+    notes: &[CapturedNote],
+    settings: &NoteCaptureSettings,
+    sample_dir: &Path,
+) -> io::Result<()> {
+    let unique_notes: Vec<u8> = {
+        let mut ns: Vec<u8> = notes.iter().map(|c| c.note).collect();
+        ns.sort_unstable();
+        ns.dedup();
+        ns
+    };
+    let key_ranges = key_ranges(&unique_notes, settings.first_note, settings.last_note);
+
+    writeln!(writer, "<group>")?;
+
+    for (note, (lokey, hikey)) in unique_notes.iter().zip(key_ranges) {
+        let mut layers: Vec<&CapturedNote> =
+            notes.iter().filter(|c| c.note == *note).collect();
+        layers.sort_unstable_by_key(|c| c.velocity);
+
+        let unique_velocities: Vec<u8> = {
+            let mut vs: Vec<u8> = layers.iter().map(|c| c.velocity).collect();
+            vs.sort_unstable();
+            vs.dedup();
+            vs
+        };
+        let vel_ranges = velocity_ranges(&unique_velocities);
+
+        for captured in &layers {
+            let (lovel, hivel) = unique_velocities
+                .iter()
+                .position(|&v| v == captured.velocity)
+                .map(|i| vel_ranges[i])
+                .expect("captured.velocity is one of the layers' velocities");
+
+            let sample_path = sample_dir.join(note_file_name(captured.note, captured.velocity));
+            writeln!(writer, "<region>")?;
+            writeln!(writer, "sample={}", sample_path.display())?;
+            writeln!(writer, "lokey={}", lokey)?;
+            writeln!(writer, "hikey={}", hikey)?;
+            writeln!(writer, "pitch_keycenter={}", note)?;
+            writeln!(writer, "lovel={}", lovel)?;
+            writeln!(writer, "hivel={}", hivel)?;
+
+            if let Some(loop_points) = captured.loop_points {
+                writeln!(writer, "loop_mode=loop_sustain")?;
+                writeln!(writer, "loop_start={}", loop_points.loop_start)?;
+                writeln!(writer, "loop_end={}", loop_points.loop_end)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loop_detect::LoopPoints;
+
+    #[test]
+    fn partition_ranges_splits_gaps_evenly() {
+        assert_eq!(partition_ranges(&[10, 20, 30], 0, 40), vec![(0, 15), (16, 25), (26, 40)]);
+    }
+
+    #[test]
+    fn partition_ranges_single_value_spans_whole_range() {
+        assert_eq!(partition_ranges(&[60], 21, 108), vec![(21, 108)]);
+    }
+
+    #[test]
+    fn key_ranges_and_velocity_ranges_delegate_to_partition_ranges() {
+        assert_eq!(key_ranges(&[21, 28], 21, 35), vec![(21, 24), (25, 35)]);
+        assert_eq!(velocity_ranges(&[32, 96]), vec![(1, 64), (65, 127)]);
+    }
+
+    fn note(n: u8, velocity: u8) -> CapturedNote {
+        CapturedNote {
+            note: n,
+            velocity,
+            samples: Vec::new(),
+            loop_points: None,
+        }
+    }
+
+    #[test]
+    fn write_sfz_emits_one_region_per_note() {
+        let notes = vec![note(21, 64), note(28, 64)];
+        let settings = NoteCaptureSettings::default();
+
+        let mut buf = Vec::new();
+        write_sfz(&mut buf, &notes, &settings, Path::new("samples")).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+
+        assert_eq!(text.matches("<region>").count(), 2);
+        assert!(text.contains("pitch_keycenter=21"));
+        assert!(text.contains("pitch_keycenter=28"));
+        assert!(text.contains("lokey=21"));
+        assert!(text.contains("hikey=108")); // settings.last_note default
+    }
+
+    #[test]
+    fn write_sfz_emits_velocity_range_per_layer() {
+        let notes = vec![note(60, 32), note(60, 96)];
+        let settings = NoteCaptureSettings::default();
+
+        let mut buf = Vec::new();
+        write_sfz(&mut buf, &notes, &settings, Path::new("samples")).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+
+        assert_eq!(text.matches("<region>").count(), 2);
+        assert!(text.contains("lovel=1"));
+        assert!(text.contains("hivel=127"));
+    }
+
+    #[test]
+    fn velocity_ranges_dedupes_repeated_values() {
+        assert_eq!(velocity_ranges(&[50, 50, 50]), vec![(1, 127)]);
+        assert_eq!(velocity_ranges(&[50, 50, 90]), vec![(1, 70), (71, 127)]);
+    }
+
+    #[test]
+    fn write_sfz_handles_repeated_velocity_without_inverting_range() {
+        let notes = vec![note(60, 50), note(60, 50), note(60, 90)];
+        let settings = NoteCaptureSettings::default();
+
+        let mut buf = Vec::new();
+        write_sfz(&mut buf, &notes, &settings, Path::new("samples")).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+
+        assert_eq!(text.matches("<region>").count(), 3);
+        assert_eq!(text.matches("lovel=1").count(), 2);
+        assert_eq!(text.matches("hivel=70").count(), 2);
+        assert_eq!(text.matches("lovel=71").count(), 1);
+        assert_eq!(text.matches("hivel=127").count(), 1);
+    }
+
+    #[test]
+    fn write_sfz_includes_loop_points_when_present() {
+        let mut with_loop = note(60, 64);
+        with_loop.loop_points = Some(LoopPoints {
+            loop_start: 100,
+            loop_end: 5000,
+        });
+        let settings = NoteCaptureSettings::default();
+
+        let mut buf = Vec::new();
+        write_sfz(&mut buf, &[with_loop], &settings, Path::new("samples")).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+
+        assert!(text.contains("loop_mode=loop_sustain"));
+        assert!(text.contains("loop_start=100"));
+        assert!(text.contains("loop_end=5000"));
+    }
+}
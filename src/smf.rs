@@ -0,0 +1,154 @@
+//! Recording a capture session as a Standard MIDI File (type 0).
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Instant;
+
+/// Ticks per quarter note used for recorded sessions, paired with a tempo of
+/// 1,000,000 microseconds (1 second) per quarter note so that each tick is
+/// exactly one millisecond of real elapsed time.
+const TICKS_PER_QUARTER: u16 = 1000;
+const TEMPO_US_PER_QUARTER: u32 = 1_000_000;
+
+/// A MIDI message logged at the elapsed time (in milliseconds since
+/// recording began) it was sent.
+#[derive(Debug, Clone, Copy)]
+struct TimedEvent {
+    elapsed_ms: u64,
+    message: [u8; 3],
+}
+
+/// Logs every Note On/Off message sent during a capture session, with real
+/// elapsed timing, so the session can be saved as a Standard MIDI File.
+pub struct SessionRecorder {
+    start: Instant,
+    events: Vec<TimedEvent>,
+}
+
+impl SessionRecorder {
+    /// Start a new recording; elapsed times are measured from this call.
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Log `message` at the current elapsed time.
+    pub fn record(&mut self, message: [u8; 3]) {
+        self.events.push(TimedEvent {
+            elapsed_ms: self.start.elapsed().as_millis() as u64,
+            message,
+        });
+    }
+
+    /// Write the recorded session to `path` as a type-0 Standard MIDI File.
+    pub fn write_smf(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut file = File::create(path)?;
+
+        file.write_all(b"MThd")?;
+        file.write_all(&6u32.to_be_bytes())?;
+        file.write_all(&0u16.to_be_bytes())?; // format 0: single track
+        file.write_all(&1u16.to_be_bytes())?; // one track
+        file.write_all(&TICKS_PER_QUARTER.to_be_bytes())?;
+
+        let track = self.track_chunk();
+        file.write_all(b"MTrk")?;
+        file.write_all(&(track.len() as u32).to_be_bytes())?;
+        file.write_all(&track)?;
+
+        Ok(())
+    }
+
+    fn track_chunk(&self) -> Vec<u8> {
+        let mut track = Vec::new();
+
+        track.extend(write_vlq(0));
+        track.extend_from_slice(&[0xFF, 0x51, 0x03]);
+        track.extend_from_slice(&TEMPO_US_PER_QUARTER.to_be_bytes()[1..]);
+
+        let mut last_ms = 0u64;
+        for event in &self.events {
+            let delta = (event.elapsed_ms - last_ms) as u32;
+            last_ms = event.elapsed_ms;
+
+            track.extend(write_vlq(delta));
+            track.extend_from_slice(&event.message);
+        }
+
+        track.extend(write_vlq(0));
+        track.extend_from_slice(&[0xFF, 0x2F, 0x00]); // End of Track
+
+        track
+    }
+}
+
+impl Default for SessionRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Encode `value` as an SMF variable-length quantity: 7-bit groups emitted
+/// high-to-low, with the continuation bit (0x80) set on all but the final
+/// byte.
+fn write_vlq(value: u32) -> Vec<u8> {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut remaining = value >> 7;
+    while remaining > 0 {
+        bytes.push(((remaining & 0x7F) as u8) | 0x80);
+        remaining >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vlq_matches_spec_worked_examples() {
+        assert_eq!(write_vlq(0x00), vec![0x00]);
+        assert_eq!(write_vlq(0x40), vec![0x40]);
+        assert_eq!(write_vlq(0x7F), vec![0x7F]);
+        assert_eq!(write_vlq(0x80), vec![0x81, 0x00]);
+        assert_eq!(write_vlq(0x2000), vec![0xC0, 0x00]);
+        assert_eq!(write_vlq(0x3FFF), vec![0xFF, 0x7F]);
+        assert_eq!(write_vlq(0x4000), vec![0x81, 0x80, 0x00]);
+        assert_eq!(write_vlq(0x100000), vec![0xC0, 0x80, 0x00]);
+        assert_eq!(write_vlq(0x1FFFFF), vec![0xFF, 0xFF, 0x7F]);
+        assert_eq!(write_vlq(0x08000000), vec![0xC0, 0x80, 0x80, 0x00]);
+        assert_eq!(write_vlq(0x0FFFFFFF), vec![0xFF, 0xFF, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn empty_session_track_is_just_tempo_and_end_of_track() {
+        let recorder = SessionRecorder::new();
+        let track = recorder.track_chunk();
+
+        assert_eq!(
+            track,
+            vec![0x00, 0xFF, 0x51, 0x03, 0x0F, 0x42, 0x40, 0x00, 0xFF, 0x2F, 0x00]
+        );
+    }
+
+    #[test]
+    fn recorded_events_appear_between_tempo_and_end_of_track() {
+        let mut recorder = SessionRecorder::new();
+        recorder.record([0x90, 60, 64]);
+        recorder.record([0x80, 60, 64]);
+
+        let track = recorder.track_chunk();
+
+        // Tempo meta event, 7 bytes: delta(1) FF 51 03 <3-byte tempo>.
+        assert_eq!(&track[0..7], &[0x00, 0xFF, 0x51, 0x03, 0x0F, 0x42, 0x40]);
+        // End of Track meta event, always the final 4 bytes: delta(1) FF 2F 00.
+        assert_eq!(&track[track.len() - 4..], &[0x00, 0xFF, 0x2F, 0x00]);
+        // Both recorded MIDI messages appear, in order, between the two.
+        let body = &track[7..track.len() - 4];
+        assert_eq!(body.windows(3).filter(|w| *w == [0x90, 60, 64]).count(), 1);
+        assert_eq!(body.windows(3).filter(|w| *w == [0x80, 60, 64]).count(), 1);
+    }
+}
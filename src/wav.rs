@@ -0,0 +1,174 @@
+//! Writing captured notes out as standalone `.wav` files.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::{CapturedNote, NoteCaptureSettings, NoteSample};
+
+/// Sample encoding to use when writing a captured note to disk.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WavSampleFormat {
+    /// 16-bit signed PCM, converted from f32 with clamping.
+    Pcm16,
+    /// 32-bit IEEE float, written as captured.
+    Float32,
+}
+
+/// Return the file name for a captured note, e.g. `note_060_vel064.wav`.
+pub fn note_file_name(note: u8, velocity: u8) -> String {
+    format!("note_{:03}_vel{:03}.wav", note, velocity)
+}
+
+/// Write a single captured note to `path` as a RIFF/WAVE file, using the
+/// channel count, sample rate, and sample format from `settings`.
+pub fn write_note_wav(
+    path: impl AsRef<Path>,
+    sample: &NoteSample,
+    settings: &NoteCaptureSettings,
+) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    write_wav(
+        &mut file,
+        sample,
+        settings.channels as u16,
+        settings.sample_rate as u32,
+        settings.wav_format,
+    )
+}
+
+/// Write each captured note to its own `.wav` file inside `dir`. Returns the
+/// paths written, in order.
+pub fn export_notes(
+    notes: &[CapturedNote],
+    settings: &NoteCaptureSettings,
+    dir: impl AsRef<Path>,
+) -> io::Result<Vec<PathBuf>> {
+    let dir = dir.as_ref();
+    notes
+        .iter()
+        .map(|captured| {
+            let path = dir.join(note_file_name(captured.note, captured.velocity));
+            write_note_wav(&path, &captured.samples, settings)?;
+            Ok(path)
+        })
+        .collect()
+}
+
+/// Write `sample` (interleaved f32, `channels` channels at `sample_rate` Hz)
+/// as a RIFF/WAVE file to `writer`, encoding it as `format`.
+fn write_wav<W: Write>(
+    writer: &mut W,
+    sample: &NoteSample,
+    channels: u16,
+    sample_rate: u32,
+    format: WavSampleFormat,
+) -> io::Result<()> {
+    let bits_per_sample: u16 = match format {
+        WavSampleFormat::Pcm16 => 16,
+        WavSampleFormat::Float32 => 32,
+    };
+    let audio_format: u16 = match format {
+        WavSampleFormat::Pcm16 => 1,  // WAVE_FORMAT_PCM
+        WavSampleFormat::Float32 => 3, // WAVE_FORMAT_IEEE_FLOAT
+    };
+
+    let block_align = channels * (bits_per_sample / 8);
+    let byte_rate = sample_rate * block_align as u32;
+    let data_size = sample.len() as u32 * (bits_per_sample as u32 / 8);
+    let riff_size = 4 + (8 + 16) + (8 + data_size);
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&riff_size.to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&audio_format.to_le_bytes())?;
+    writer.write_all(&channels.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&byte_rate.to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&bits_per_sample.to_le_bytes())?;
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+
+    match format {
+        WavSampleFormat::Pcm16 => {
+            for s in sample {
+                let clamped = s.clamp(-1.0, 1.0);
+                let v = (clamped * i16::MAX as f32) as i16;
+                writer.write_all(&v.to_le_bytes())?;
+            }
+        }
+        WavSampleFormat::Float32 => {
+            for s in sample {
+                writer.write_all(&s.to_le_bytes())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn note_file_name_pads_note_and_velocity() {
+        assert_eq!(note_file_name(9, 7), "note_009_vel007.wav");
+    }
+
+    #[test]
+    fn pcm16_header_and_clamped_samples() {
+        let mut buf = Vec::new();
+        let samples: NoteSample = vec![0.0, 0.5, -1.5, 1.5];
+        write_wav(&mut buf, &samples, 1, 44100, WavSampleFormat::Pcm16).unwrap();
+
+        assert_eq!(&buf[0..4], b"RIFF");
+        assert_eq!(&buf[8..12], b"WAVE");
+        assert_eq!(&buf[12..16], b"fmt ");
+        assert_eq!(u16::from_le_bytes([buf[20], buf[21]]), 1); // WAVE_FORMAT_PCM
+        assert_eq!(u16::from_le_bytes([buf[22], buf[23]]), 1); // channels
+        assert_eq!(u32::from_le_bytes(buf[24..28].try_into().unwrap()), 44100);
+        assert_eq!(u16::from_le_bytes([buf[34], buf[35]]), 16); // bits per sample
+        assert_eq!(&buf[36..40], b"data");
+
+        let data_size = u32::from_le_bytes(buf[40..44].try_into().unwrap());
+        assert_eq!(data_size, (samples.len() * 2) as u32);
+        assert_eq!(buf.len(), 44 + data_size as usize);
+
+        let data = &buf[44..];
+        assert_eq!(i16::from_le_bytes([data[0], data[1]]), 0);
+        // 1.5 clamps to 1.0 before converting to i16.
+        assert_eq!(i16::from_le_bytes([data[6], data[7]]), i16::MAX);
+    }
+
+    #[test]
+    fn float32_writes_samples_unmodified() {
+        let mut buf = Vec::new();
+        let samples: NoteSample = vec![0.25, -0.75];
+        write_wav(&mut buf, &samples, 2, 48000, WavSampleFormat::Float32).unwrap();
+
+        assert_eq!(u16::from_le_bytes([buf[20], buf[21]]), 3); // WAVE_FORMAT_IEEE_FLOAT
+        assert_eq!(u16::from_le_bytes([buf[34], buf[35]]), 32); // bits per sample
+
+        let data = &buf[44..];
+        assert_eq!(f32::from_le_bytes(data[0..4].try_into().unwrap()), 0.25);
+        assert_eq!(f32::from_le_bytes(data[4..8].try_into().unwrap()), -0.75);
+    }
+
+    #[test]
+    fn empty_sample_writes_zero_length_data_chunk() {
+        let mut buf = Vec::new();
+        write_wav(&mut buf, &Vec::new(), 1, 44100, WavSampleFormat::Pcm16).unwrap();
+
+        let data_size = u32::from_le_bytes(buf[40..44].try_into().unwrap());
+        assert_eq!(data_size, 0);
+        assert_eq!(buf.len(), 44);
+    }
+}